@@ -1,110 +1,817 @@
-use std::sync::{Arc, Mutex};
-use std::collections::VecDeque;
-use bit_set::BitSet;
-
-type Value = i32;
-const CHUNK_SIZE: usize = 64;  // Adjust based on your needs
-
-/// Represents a chunk of storage.
-///
-/// A chunk contains a fixed-size array of optional values and a free list of indices pointing
-/// to unoccupied slots within the array.
-struct Chunk {
-    /// Storage for values. `None` indicates an unoccupied slot.
-    values: [Option<Value>; CHUNK_SIZE],
-    /// A list of indices pointing to unoccupied slots within the `values` array.
-    free_indices: VecDeque<usize>,
-}
-
-impl Chunk {
-    /// Constructs a new `Chunk` with all slots unoccupied.
-    fn new() -> Self {
-        Chunk {
-            values: Default::default(),
-            free_indices: (0..CHUNK_SIZE).collect(),
-        }
-    }
-
-    /// Tries to allocate the given value within the chunk.
-    ///
-    /// Returns the index within the chunk where the value was stored, or `None` if the chunk is full.
-    fn allocate(&mut self, value: Value) -> Option<usize> {
-        self.free_indices.pop_front().map(|index| {
-            self.values[index] = Some(value);
-            index
-        })
-    }
-
-    /// Deallocates the value at the given index within the chunk.
-    ///
-    /// If the provided index is out of bounds, this function does nothing.
-    fn deallocate(&mut self, index: usize) {
-        if index < CHUNK_SIZE {
-            self.values[index] = None;
-            self.free_indices.push_back(index);
-        }
-    }
-
-    /// Checks if the chunk has any unoccupied slots.
-    ///
-    /// Returns `true` if there's at least one free slot, otherwise `false`.
-    fn has_free_slot(&self) -> bool {
-        !self.free_indices.is_empty()
-    }
-}
-
-lazy_static! {
-    /// A dynamic list of chunks for storing values.
-    static ref CHUNKS: Arc<Mutex<Vec<Chunk>>> = Arc::new(Mutex::new(Vec::new()));
-    /// A bit set tracking chunks with free slots. 
-    /// A set bit at index `i` indicates that `CHUNKS[i]` has at least one free slot.
-    static ref FREE_CHUNKS: Arc<Mutex<BitSet>> = Arc::new(Mutex::new(BitSet::new()));
-}
-
-/// Allocates the given value and returns its location as a (chunk_index, value_index) tuple.
-///
-/// If no chunks with free slots are available, a new chunk is created.
-fn allocate_value(value: Value) -> (usize, usize) {
-    let mut chunks = CHUNKS.lock().unwrap();
-    let mut free_chunks = FREE_CHUNKS.lock().unwrap();
-
-    // Find a chunk with a free slot
-    let chunk_index = free_chunks.iter().next()
-        .unwrap_or_else(|| {
-            let new_chunk = Chunk::new();
-            chunks.push(new_chunk);
-            let index = chunks.len() - 1;
-            free_chunks.insert(index);
-            index
-        });
-
-    let chunk = &mut chunks[chunk_index];
-    let value_index = chunk.allocate(value).unwrap();
-
-    if !chunk.has_free_slot() {
-        free_chunks.remove(chunk_index);
-    }
-
-    (chunk_index, value_index)
-}
-
-/// Deallocates the value at the specified location.
-///
-/// If the provided chunk_index is out of bounds, this function does nothing.
-fn deallocate_value(chunk_index: usize, value_index: usize) {
-    let mut chunks = CHUNKS.lock().unwrap();
-    let mut free_chunks = FREE_CHUNKS.lock().unwrap();
-
-    if let Some(chunk) = chunks.get_mut(chunk_index) {
-        chunk.deallocate(value_index);
-        if chunk.has_free_slot() {
-            free_chunks.insert(chunk_index);
-        }
-    }
-}
-
-fn main() {
-    let (chunk_index, value_index) = allocate_value(42);
-    println!("Value allocated in chunk {} at index {}", chunk_index, value_index);
-    deallocate_value(chunk_index, value_index);
-}
+#[macro_use]
+extern crate lazy_static;
+extern crate pyo3;
+
+use std::sync::{Arc, Mutex};
+use bit_set::BitSet;
+use memmap2::{MmapMut, MmapOptions};
+use pyo3::exceptions::{PyMemoryError, PyRuntimeError};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+type Value = i32;
+const CHUNK_SIZE: usize = 64;  // Adjust based on your needs
+
+/// A handle to a value stored in the chunked arena.
+///
+/// Carries the slot's generation alongside its location, so a handle to a slot that was
+/// since freed and reused is rejected instead of silently aliasing the new occupant. Also
+/// exposed to Python as a `#[pyclass]` so it can round-trip through an `Arena`.
+#[pyclass]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Handle {
+    #[pyo3(get)]
+    chunk_index: usize,
+    #[pyo3(get)]
+    value_index: usize,
+    #[pyo3(get)]
+    generation: u32,
+}
+
+/// Errors that can occur while allocating, deallocating, or looking up a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AllocError {
+    /// A `Mutex` guarding the allocator's shared state was poisoned by a panicking thread.
+    LockPoisoned,
+    /// The allocator is out of capacity, e.g. a new `Chunk` could not be pushed.
+    OutOfCapacity,
+    /// The handle's generation no longer matches the slot's current generation, or its
+    /// chunk/value index is out of bounds. Also returned by `Arena::try_allocate_payload`
+    /// for an empty payload.
+    InvalidHandle,
+}
+
+impl std::fmt::Display for AllocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocError::LockPoisoned => write!(f, "allocator lock was poisoned"),
+            AllocError::OutOfCapacity => write!(f, "allocator is out of capacity"),
+            AllocError::InvalidHandle => write!(f, "handle is invalid or stale"),
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+impl From<AllocError> for PyErr {
+    fn from(err: AllocError) -> PyErr {
+        match err {
+            AllocError::LockPoisoned => PyRuntimeError::new_err(err.to_string()),
+            AllocError::OutOfCapacity => PyMemoryError::new_err(err.to_string()),
+            AllocError::InvalidHandle => PyRuntimeError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// A spill region freed but not yet reclaimed, queued by [`SpillChunk::free`] and reused by
+/// the next reservation big enough to hold it.
+#[derive(Debug, Clone, Copy)]
+struct DataEntryToFree {
+    offset: usize,
+    len: usize,
+}
+
+/// Per-slot storage for a [`Chunk`]. The common case of a single-value payload is kept
+/// inline in the slot itself with no second indirection; a payload of more than one value
+/// instead spills into a secondary [`SpillChunk`].
+enum OccupiedEnum<T> {
+    /// The slot is unoccupied.
+    Free,
+    /// The slot's payload is a single value, stored directly in the slot.
+    Inline(T),
+    /// The slot's payload overflowed a single value; the actual values live in spill chunk
+    /// `chunk`, `len` values long starting at `offset`.
+    Spilled { chunk: usize, offset: usize, len: usize },
+}
+
+/// Secondary, append-mostly storage for payloads too long to store inline in a [`Chunk`].
+///
+/// `values[i]` is `None` exactly where no live value occupies slot `i`, whether because
+/// that range was never written or because it was freed and is awaiting reuse.
+struct SpillChunk<T> {
+    values: Vec<Option<T>>,
+    pending_frees: Vec<DataEntryToFree>,
+}
+
+impl<T> SpillChunk<T> {
+    fn new() -> Self {
+        SpillChunk { values: Vec::new(), pending_frees: Vec::new() }
+    }
+
+    /// Reserves `len` contiguous slots, reusing a pending free region big enough to hold
+    /// them (first fit) before growing `values`. A reused region larger than `len` has its
+    /// remainder pushed back onto `pending_frees` rather than leaked.
+    fn reserve(&mut self, len: usize) -> usize {
+        if let Some(pos) = self.pending_frees.iter().position(|entry| entry.len >= len) {
+            let entry = self.pending_frees.remove(pos);
+            if entry.len > len {
+                self.pending_frees.push(DataEntryToFree { offset: entry.offset + len, len: entry.len - len });
+            }
+            return entry.offset;
+        }
+        let offset = self.values.len();
+        self.values.resize_with(offset + len, || None);
+        offset
+    }
+
+    /// Writes `payload` into the slots reserved at `offset`.
+    fn write(&mut self, offset: usize, payload: Vec<T>) {
+        for (i, value) in payload.into_iter().enumerate() {
+            self.values[offset + i] = Some(value);
+        }
+    }
+
+    /// Reads the `len` values starting at `offset`.
+    fn read(&self, offset: usize, len: usize) -> Vec<&T> {
+        self.values[offset..offset + len]
+            .iter()
+            .map(|slot| slot.as_ref().expect("spilled slot should be occupied"))
+            .collect()
+    }
+
+    /// Drops the `len` values starting at `offset` and queues the region for reuse.
+    fn free(&mut self, offset: usize, len: usize) {
+        for slot in &mut self.values[offset..offset + len] {
+            *slot = None;
+        }
+        self.pending_frees.push(DataEntryToFree { offset, len });
+    }
+}
+
+/// Pluggable occupancy tracking for a fixed-size chunk of slots, decoupled from the value
+/// storage it tracks.
+trait BucketOccupied {
+    /// Constructs a tracker for `capacity` slots, all initially free.
+    fn new(capacity: usize) -> Self;
+    /// Marks the slot at `ix` as occupied.
+    fn occupy(&mut self, ix: usize);
+    /// Marks the slot at `ix` as free.
+    fn free(&mut self, ix: usize);
+    /// Returns `true` if the slot at `ix` is free.
+    fn is_free(&self, ix: usize) -> bool;
+}
+
+/// Default `BucketOccupied` implementation backed by a packed `BitSet` occupancy mask —
+/// one bit per slot, set while the slot is free.
+struct BitSetOccupied {
+    free: BitSet,
+}
+
+impl BucketOccupied for BitSetOccupied {
+    fn new(capacity: usize) -> Self {
+        let mut free = BitSet::with_capacity(capacity);
+        for ix in 0..capacity {
+            free.insert(ix);
+        }
+        BitSetOccupied { free }
+    }
+
+    fn occupy(&mut self, ix: usize) {
+        self.free.remove(ix);
+    }
+
+    fn free(&mut self, ix: usize) {
+        self.free.insert(ix);
+    }
+
+    fn is_free(&self, ix: usize) -> bool {
+        self.free.contains(ix)
+    }
+}
+
+/// Represents a chunk of storage for values of type `T`.
+///
+/// A chunk contains a fixed-size list of tagged slots, a `BucketOccupied` tracker that is
+/// the source of truth for which slots are free, and a generation counter per slot used to
+/// detect stale handles. Generic over `T` so the same chunk layout backs both the `i32`
+/// arena below and the Python-facing `Arena<PyObject>`. A slot's `OccupiedEnum` value is
+/// only meaningful while `occupancy` marks it occupied; `Free` is otherwise just a
+/// placeholder.
+struct Chunk<T> {
+    /// One tagged slot per index.
+    slots: Vec<OccupiedEnum<T>>,
+    /// Tracks which slots in `slots` are free.
+    occupancy: BitSetOccupied,
+    /// Generation counter for each slot, bumped every time the slot is deallocated.
+    generations: [u32; CHUNK_SIZE],
+}
+
+impl<T> Chunk<T> {
+    /// Constructs a new `Chunk` with all slots unoccupied.
+    fn new() -> Self {
+        Chunk {
+            slots: (0..CHUNK_SIZE).map(|_| OccupiedEnum::Free).collect(),
+            occupancy: BitSetOccupied::new(CHUNK_SIZE),
+            generations: [0; CHUNK_SIZE],
+        }
+    }
+
+    /// Finds a free slot without occupying it, so the caller can prepare the occupant
+    /// (which may itself need to reserve spill space) before committing it.
+    fn reserve_free_slot(&self) -> Option<usize> {
+        (0..CHUNK_SIZE).find(|&ix| self.occupancy.is_free(ix))
+    }
+
+    /// Stores `occupant` in the slot at `index` and returns its current generation.
+    fn occupy(&mut self, index: usize, occupant: OccupiedEnum<T>) -> u32 {
+        self.slots[index] = occupant;
+        self.occupancy.occupy(index);
+        self.generations[index]
+    }
+
+    /// Replaces the occupant of `index` in place, provided `generation` matches, without
+    /// bumping the slot's generation. Returns the previous occupant.
+    fn replace(&mut self, index: usize, generation: u32, occupant: OccupiedEnum<T>) -> Option<OccupiedEnum<T>> {
+        if index >= CHUNK_SIZE || self.occupancy.is_free(index) || generation != self.generations[index] {
+            return None;
+        }
+        Some(std::mem::replace(&mut self.slots[index], occupant))
+    }
+
+    /// Deallocates the slot at the given index, provided `generation` matches the slot's
+    /// current generation.
+    ///
+    /// Returns the freed occupant, or `None` if the index was out of bounds, the slot was
+    /// already free, or the generation did not match (a stale handle).
+    fn deallocate(&mut self, index: usize, generation: u32) -> Option<OccupiedEnum<T>> {
+        if index >= CHUNK_SIZE || self.occupancy.is_free(index) || generation != self.generations[index] {
+            return None;
+        }
+        self.occupancy.free(index);
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        Some(std::mem::replace(&mut self.slots[index], OccupiedEnum::Free))
+    }
+
+    /// Reads the occupant at the given index, provided `generation` matches the slot's
+    /// current generation.
+    fn slot(&self, index: usize, generation: u32) -> Option<&OccupiedEnum<T>> {
+        if index >= CHUNK_SIZE || self.occupancy.is_free(index) || generation != self.generations[index] {
+            return None;
+        }
+        Some(&self.slots[index])
+    }
+
+    /// Checks if the chunk has any unoccupied slots.
+    ///
+    /// Returns `true` if there's at least one free slot, otherwise `false`.
+    fn has_free_slot(&self) -> bool {
+        (0..CHUNK_SIZE).any(|ix| self.occupancy.is_free(ix))
+    }
+
+    /// Number of occupied slots in the chunk.
+    fn live_count(&self) -> usize {
+        (0..CHUNK_SIZE).filter(|&ix| !self.occupancy.is_free(ix)).count()
+    }
+
+    /// Iterates over the `(value_index, generation, occupant)` of every live slot.
+    fn live_slots(&self) -> impl Iterator<Item = (usize, u32, &OccupiedEnum<T>)> + '_ {
+        (0..CHUNK_SIZE)
+            .filter(move |&ix| !self.occupancy.is_free(ix))
+            .map(move |ix| (ix, self.generations[ix], &self.slots[ix]))
+    }
+}
+
+/// A generic, generational arena of `T` values backed by a growable list of fixed-size
+/// chunks.
+///
+/// Owns its chunk storage and free-chunk bitset directly, so independent arenas (e.g. one
+/// per Python object type, or the global `VALUES` arena below) can coexist without sharing
+/// state. A single `T` allocates inline in its primary chunk; a multi-value payload
+/// (`try_allocate_payload`) is stored whole in `spill_chunks`.
+struct Arena<T> {
+    chunks: Vec<Chunk<T>>,
+    free_chunks: BitSet,
+    spill_chunks: Vec<SpillChunk<T>>,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Arena::new()
+    }
+}
+
+impl<T> Arena<T> {
+    /// Constructs an empty arena.
+    fn new() -> Self {
+        Arena { chunks: Vec::new(), free_chunks: BitSet::new(), spill_chunks: Vec::new() }
+    }
+
+    /// Finds a chunk with a free primary slot, creating a new one if every existing chunk
+    /// is full.
+    fn chunk_with_free_slot(&mut self) -> Result<usize, AllocError> {
+        if let Some(index) = self.free_chunks.iter().next() {
+            return Ok(index);
+        }
+        self.chunks.try_reserve(1).map_err(|_| AllocError::OutOfCapacity)?;
+        self.chunks.push(Chunk::new());
+        let index = self.chunks.len() - 1;
+        self.free_chunks.insert(index);
+        Ok(index)
+    }
+
+    /// Tries to allocate `value` as a single inline slot, creating a new chunk if every
+    /// existing chunk is full.
+    fn try_allocate_value(&mut self, value: T) -> Result<Handle, AllocError> {
+        self.try_allocate_payload(vec![value])
+    }
+
+    /// Tries to allocate `payload`. A single-value payload is packed inline; a longer one
+    /// spills into a secondary [`SpillChunk`], tagged as `Spilled` in its primary slot.
+    fn try_allocate_payload(&mut self, mut payload: Vec<T>) -> Result<Handle, AllocError> {
+        if payload.is_empty() {
+            return Err(AllocError::InvalidHandle);
+        }
+
+        let occupant = if payload.len() == 1 {
+            OccupiedEnum::Inline(payload.pop().unwrap())
+        } else {
+            if self.spill_chunks.is_empty() {
+                self.spill_chunks.push(SpillChunk::new());
+            }
+            let spill_chunk = self.spill_chunks.len() - 1;
+            let len = payload.len();
+            let offset = self.spill_chunks[spill_chunk].reserve(len);
+            self.spill_chunks[spill_chunk].write(offset, payload);
+            OccupiedEnum::Spilled { chunk: spill_chunk, offset, len }
+        };
+
+        let chunk_index = self.chunk_with_free_slot()?;
+        let chunk = &mut self.chunks[chunk_index];
+        let value_index = chunk.reserve_free_slot().ok_or(AllocError::OutOfCapacity)?;
+        let generation = chunk.occupy(value_index, occupant);
+
+        if !chunk.has_free_slot() {
+            self.free_chunks.remove(chunk_index);
+        }
+
+        Ok(Handle { chunk_index, value_index, generation })
+    }
+
+    /// Tries to deallocate the value identified by `handle`.
+    fn try_deallocate_value(&mut self, handle: Handle) -> Result<(), AllocError> {
+        let chunk = self.chunks.get_mut(handle.chunk_index).ok_or(AllocError::InvalidHandle)?;
+        let occupant = chunk.deallocate(handle.value_index, handle.generation).ok_or(AllocError::InvalidHandle)?;
+        self.free_chunks.insert(handle.chunk_index);
+        if let OccupiedEnum::Spilled { chunk: spill_chunk, offset, len } = occupant {
+            self.spill_chunks[spill_chunk].free(offset, len);
+        }
+        Ok(())
+    }
+
+    /// Tries to read the value identified by `handle`. For a `Spilled` handle this returns
+    /// only the first value of the payload; use [`Arena::try_get_payload`] for the rest.
+    fn try_get_value(&self, handle: Handle) -> Result<&T, AllocError> {
+        match self.chunks.get(handle.chunk_index)
+            .and_then(|chunk| chunk.slot(handle.value_index, handle.generation))
+            .ok_or(AllocError::InvalidHandle)?
+        {
+            OccupiedEnum::Free => unreachable!("Chunk::slot never returns a free slot"),
+            OccupiedEnum::Inline(value) => Ok(value),
+            OccupiedEnum::Spilled { chunk, offset, .. } => {
+                Ok(self.spill_chunks[*chunk].read(*offset, 1)[0])
+            }
+        }
+    }
+
+    /// Tries to read the full payload identified by `handle`, one value for an inline slot
+    /// or the stored sequence for a spilled one.
+    fn try_get_payload(&self, handle: Handle) -> Result<Vec<&T>, AllocError> {
+        match self.chunks.get(handle.chunk_index)
+            .and_then(|chunk| chunk.slot(handle.value_index, handle.generation))
+            .ok_or(AllocError::InvalidHandle)?
+        {
+            OccupiedEnum::Free => unreachable!("Chunk::slot never returns a free slot"),
+            OccupiedEnum::Inline(value) => Ok(vec![value]),
+            OccupiedEnum::Spilled { chunk, offset, len } => Ok(self.spill_chunks[*chunk].read(*offset, *len)),
+        }
+    }
+
+    /// Overwrites the value at `handle` in place with a single inline value, keeping its
+    /// generation unchanged and reclaiming any spill region the previous payload held.
+    fn try_set_value(&mut self, handle: Handle, value: T) -> Result<(), AllocError> {
+        let chunk = self.chunks.get_mut(handle.chunk_index).ok_or(AllocError::InvalidHandle)?;
+        let previous = chunk.replace(handle.value_index, handle.generation, OccupiedEnum::Inline(value))
+            .ok_or(AllocError::InvalidHandle)?;
+        if let OccupiedEnum::Spilled { chunk: spill_chunk, offset, len } = previous {
+            self.spill_chunks[spill_chunk].free(offset, len);
+        }
+        Ok(())
+    }
+
+    /// Number of live values currently stored in the arena.
+    fn len(&self) -> usize {
+        self.chunks.iter().map(Chunk::live_count).sum()
+    }
+
+    /// Iterates over the handle and first value of every live slot in the arena.
+    fn iter(&self) -> impl Iterator<Item = (Handle, &T)> + '_ {
+        self.chunks.iter().enumerate().flat_map(move |(chunk_index, chunk)| {
+            chunk.live_slots().map(move |(value_index, generation, occupant)| {
+                let value = match occupant {
+                    OccupiedEnum::Free => unreachable!("Chunk::live_slots never yields a free slot"),
+                    OccupiedEnum::Inline(value) => value,
+                    OccupiedEnum::Spilled { chunk, offset, .. } => self.spill_chunks[*chunk].read(*offset, 1)[0],
+                };
+                (Handle { chunk_index, value_index, generation }, value)
+            })
+        })
+    }
+}
+
+lazy_static! {
+    /// The global arena backing the free-standing `try_*_value` functions below.
+    static ref VALUES: Arc<Mutex<Arena<Value>>> = Arc::new(Mutex::new(Arena::new()));
+}
+
+/// Python-visible arena of arbitrary Python objects, backed by `Arena<PyObject>`.
+///
+/// Unlike the `handle_pyobject` helper in `Python2RustTypes.rs` (which stores a string's
+/// length or an integer's value, not the object), `allocate` stores the Python object
+/// itself, so it can be read back unchanged via `arena[handle]`.
+#[pyclass(name = "Arena")]
+#[derive(Default)]
+struct PyArena {
+    inner: Arena<PyObject>,
+}
+
+#[pymethods]
+impl PyArena {
+    #[new]
+    fn new() -> Self {
+        PyArena::default()
+    }
+
+    /// Stores `value` in the arena and returns a `Handle` that round-trips to it.
+    fn allocate(&mut self, value: PyObject) -> PyResult<Handle> {
+        Ok(self.inner.try_allocate_value(value)?)
+    }
+
+    /// Removes the value identified by `handle` from the arena.
+    fn deallocate(&mut self, handle: Handle) -> PyResult<()> {
+        Ok(self.inner.try_deallocate_value(handle)?)
+    }
+
+    fn __getitem__(&self, py: Python<'_>, handle: Handle) -> PyResult<PyObject> {
+        Ok(self.inner.try_get_value(handle)?.clone_ref(py))
+    }
+
+    fn __setitem__(&mut self, handle: Handle, value: PyObject) -> PyResult<()> {
+        Ok(self.inner.try_set_value(handle, value)?)
+    }
+
+    fn __delitem__(&mut self, handle: Handle) -> PyResult<()> {
+        Ok(self.inner.try_deallocate_value(handle)?)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns a Python iterator over the `(handle, value)` pairs of every live slot.
+    fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let items: Vec<(PyObject, PyObject)> = self.inner.iter()
+            .map(|(handle, value)| (handle.into_py(py), value.clone_ref(py)))
+            .collect();
+        let list = PyList::new(py, items);
+        Ok(list.call_method0("__iter__")?.to_object(py))
+    }
+}
+
+/// Errors that can occur while opening, flushing, or closing a [`MappedStore`].
+#[derive(Debug)]
+enum MmapStoreError {
+    /// The backing file could not be opened, grown, or mapped.
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for MmapStoreError {
+    fn from(err: std::io::Error) -> Self {
+        MmapStoreError::Io(err)
+    }
+}
+
+impl std::fmt::Display for MmapStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MmapStoreError::Io(err) => write!(f, "backing store I/O error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MmapStoreError {}
+
+/// On-disk layout of one chunk's region: a packed occupancy bitset (one bit per slot, set
+/// while the slot is free), followed by one `u32` generation counter per slot, followed by
+/// the raw `values` array. Unlike the in-process [`Chunk`], the mapped layout has no
+/// inline/spill distinction — every slot is a fixed-size `Value`.
+const CHUNK_OCCUPANCY_BYTES: usize = (CHUNK_SIZE + 7) / 8;
+const CHUNK_GENERATIONS_BYTES: usize = CHUNK_SIZE * std::mem::size_of::<u32>();
+const CHUNK_VALUES_OFFSET: usize = CHUNK_OCCUPANCY_BYTES + CHUNK_GENERATIONS_BYTES;
+const CHUNK_REGION_BYTES: usize = CHUNK_VALUES_OFFSET + CHUNK_SIZE * std::mem::size_of::<Value>();
+
+/// Reinterprets the value at `index` within a chunk's raw byte `region` as a `&Value`.
+///
+/// `region` must be at least `CHUNK_REGION_BYTES` long and `index` must be `< CHUNK_SIZE`.
+fn get_from_parts(region: &[u8], index: usize) -> &Value {
+    let offset = CHUNK_VALUES_OFFSET + index * std::mem::size_of::<Value>();
+    let bytes = &region[offset..offset + std::mem::size_of::<Value>()];
+    unsafe { &*(bytes.as_ptr() as *const Value) }
+}
+
+/// Mutable counterpart of [`get_from_parts`].
+fn get_mut_from_parts(region: &mut [u8], index: usize) -> &mut Value {
+    let offset = CHUNK_VALUES_OFFSET + index * std::mem::size_of::<Value>();
+    let bytes = &mut region[offset..offset + std::mem::size_of::<Value>()];
+    unsafe { &mut *(bytes.as_mut_ptr() as *mut Value) }
+}
+
+/// Returns `true` if the occupancy bit for `index` within `region` is set, i.e. the slot is free.
+fn is_free_in_parts(region: &[u8], index: usize) -> bool {
+    region[index / 8] & (1 << (index % 8)) != 0
+}
+
+/// Sets the occupancy bit for `index` within `region` to `occupied`.
+fn set_occupied_in_parts(region: &mut [u8], index: usize, occupied: bool) {
+    let bit = 1u8 << (index % 8);
+    if occupied {
+        region[index / 8] &= !bit;
+    } else {
+        region[index / 8] |= bit;
+    }
+}
+
+fn generation_in_parts(region: &[u8], index: usize) -> u32 {
+    let offset = CHUNK_OCCUPANCY_BYTES + index * std::mem::size_of::<u32>();
+    u32::from_le_bytes(region[offset..offset + 4].try_into().unwrap())
+}
+
+fn set_generation_in_parts(region: &mut [u8], index: usize, generation: u32) {
+    let offset = CHUNK_OCCUPANCY_BYTES + index * std::mem::size_of::<u32>();
+    region[offset..offset + 4].copy_from_slice(&generation.to_le_bytes());
+}
+
+/// A memory-mapped, file-backed variant of the chunked arena.
+///
+/// Chunks live in a single mapped file laid out as consecutive `CHUNK_REGION_BYTES`
+/// regions, so allocations made through a `MappedStore` survive process restarts. The
+/// free-chunk bitset isn't itself persisted; `open` rebuilds it by scanning each chunk's
+/// occupancy header.
+struct MappedStore {
+    file: File,
+    mmap: MmapMut,
+    chunk_count: usize,
+    free_chunks: BitSet,
+}
+
+impl MappedStore {
+    /// Opens (creating if necessary) the backing file at `path`, growing it to hold at
+    /// least one chunk, and rebuilds the free-chunk bitset by scanning occupancy headers.
+    fn open(path: &Path) -> Result<Self, MmapStoreError> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let existing_len = file.metadata()?.len() as usize;
+        let is_fresh_file = existing_len == 0;
+        let chunk_count = (existing_len / CHUNK_REGION_BYTES).max(1);
+        if existing_len < chunk_count * CHUNK_REGION_BYTES {
+            file.set_len((chunk_count * CHUNK_REGION_BYTES) as u64)?;
+        }
+
+        let mmap = unsafe { MmapOptions::new().map_mut(&file)? };
+
+        let mut store = MappedStore { file, mmap, chunk_count, free_chunks: BitSet::new() };
+
+        if is_fresh_file {
+            // A freshly created file is all zero bytes, i.e. every occupancy bit clear
+            // (occupied); mark chunk 0's slots free before the scan below, the same way
+            // `grow_by_one_chunk` does for later growth.
+            let region = store.chunk_region_mut(0);
+            for ix in 0..CHUNK_SIZE {
+                set_occupied_in_parts(region, ix, false);
+            }
+        }
+
+        for chunk_index in 0..store.chunk_count {
+            let region = store.chunk_region(chunk_index);
+            if (0..CHUNK_SIZE).any(|ix| is_free_in_parts(region, ix)) {
+                store.free_chunks.insert(chunk_index);
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn chunk_region(&self, chunk_index: usize) -> &[u8] {
+        let start = chunk_index * CHUNK_REGION_BYTES;
+        &self.mmap[start..start + CHUNK_REGION_BYTES]
+    }
+
+    fn chunk_region_mut(&mut self, chunk_index: usize) -> &mut [u8] {
+        let start = chunk_index * CHUNK_REGION_BYTES;
+        &mut self.mmap[start..start + CHUNK_REGION_BYTES]
+    }
+
+    /// Grows the backing file by one chunk region and returns the new chunk's index.
+    fn grow_by_one_chunk(&mut self) -> Result<usize, MmapStoreError> {
+        let new_chunk_index = self.chunk_count;
+        let new_len = (self.chunk_count + 1) * CHUNK_REGION_BYTES;
+        self.file.set_len(new_len as u64)?;
+        self.mmap = unsafe { MmapOptions::new().map_mut(&self.file)? };
+        // A freshly grown region is all zero bytes, i.e. every occupancy bit clear
+        // (occupied); mark every slot free before it's used.
+        let region = self.chunk_region_mut(new_chunk_index);
+        for ix in 0..CHUNK_SIZE {
+            set_occupied_in_parts(region, ix, false);
+        }
+        self.chunk_count += 1;
+        self.free_chunks.insert(new_chunk_index);
+        Ok(new_chunk_index)
+    }
+
+    /// Tries to allocate `value`, growing the backing file by one chunk if every existing
+    /// chunk is full.
+    fn try_allocate_value(&mut self, value: Value) -> Result<Handle, AllocError> {
+        let chunk_index = match self.free_chunks.iter().next() {
+            Some(index) => index,
+            None => self.grow_by_one_chunk().map_err(|_| AllocError::OutOfCapacity)?,
+        };
+
+        let region = self.chunk_region_mut(chunk_index);
+        let value_index = (0..CHUNK_SIZE)
+            .find(|&ix| is_free_in_parts(region, ix))
+            .ok_or(AllocError::OutOfCapacity)?;
+
+        *get_mut_from_parts(region, value_index) = value;
+        set_occupied_in_parts(region, value_index, true);
+        let generation = generation_in_parts(region, value_index);
+
+        if !(0..CHUNK_SIZE).any(|ix| is_free_in_parts(region, ix)) {
+            self.free_chunks.remove(chunk_index);
+        }
+
+        Ok(Handle { chunk_index, value_index, generation })
+    }
+
+    /// Tries to deallocate the value identified by `handle`.
+    fn try_deallocate_value(&mut self, handle: Handle) -> Result<(), AllocError> {
+        if handle.chunk_index >= self.chunk_count {
+            return Err(AllocError::InvalidHandle);
+        }
+        let region = self.chunk_region_mut(handle.chunk_index);
+        if is_free_in_parts(region, handle.value_index)
+            || generation_in_parts(region, handle.value_index) != handle.generation
+        {
+            return Err(AllocError::InvalidHandle);
+        }
+        set_occupied_in_parts(region, handle.value_index, false);
+        let next_generation = generation_in_parts(region, handle.value_index).wrapping_add(1);
+        set_generation_in_parts(region, handle.value_index, next_generation);
+        self.free_chunks.insert(handle.chunk_index);
+        Ok(())
+    }
+
+    /// Tries to read the value identified by `handle`.
+    fn try_get_value(&self, handle: Handle) -> Result<Value, AllocError> {
+        if handle.chunk_index >= self.chunk_count {
+            return Err(AllocError::InvalidHandle);
+        }
+        let region = self.chunk_region(handle.chunk_index);
+        if is_free_in_parts(region, handle.value_index)
+            || generation_in_parts(region, handle.value_index) != handle.generation
+        {
+            return Err(AllocError::InvalidHandle);
+        }
+        Ok(*get_from_parts(region, handle.value_index))
+    }
+
+    /// Flushes pending writes to the backing file.
+    fn flush(&self) -> Result<(), MmapStoreError> {
+        self.mmap.flush()?;
+        Ok(())
+    }
+
+    /// Flushes and closes the backing file.
+    fn close(self) -> Result<(), MmapStoreError> {
+        self.flush()
+    }
+
+    /// Number of live values currently stored across all chunk regions.
+    fn len(&self) -> usize {
+        (0..self.chunk_count)
+            .map(|chunk_index| {
+                let region = self.chunk_region(chunk_index);
+                (0..CHUNK_SIZE).filter(|&ix| !is_free_in_parts(region, ix)).count()
+            })
+            .sum()
+    }
+}
+
+/// Python-visible, file-backed counterpart to [`PyArena`], for values that should survive
+/// process restarts.
+#[pyclass(name = "MappedArena")]
+struct PyMappedArena {
+    inner: MappedStore,
+}
+
+#[pymethods]
+impl PyMappedArena {
+    /// Opens (creating if necessary) the backing file at `path`.
+    #[new]
+    fn open(path: &str) -> PyResult<Self> {
+        let inner = MappedStore::open(Path::new(path)).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        Ok(PyMappedArena { inner })
+    }
+
+    /// Stores `value` in the arena and returns a `Handle` that round-trips to it.
+    fn allocate(&mut self, value: Value) -> PyResult<Handle> {
+        Ok(self.inner.try_allocate_value(value)?)
+    }
+
+    /// Removes the value identified by `handle` from the arena.
+    fn deallocate(&mut self, handle: Handle) -> PyResult<()> {
+        Ok(self.inner.try_deallocate_value(handle)?)
+    }
+
+    fn __getitem__(&self, handle: Handle) -> PyResult<Value> {
+        Ok(self.inner.try_get_value(handle)?)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Flushes pending writes to the backing file.
+    fn flush(&self) -> PyResult<()> {
+        Ok(self.inner.flush().map_err(|err| PyRuntimeError::new_err(err.to_string()))?)
+    }
+}
+
+/// Tries to allocate the given value in the global `VALUES` arena and returns a `Handle`
+/// identifying its location and generation.
+///
+/// Returns `Err(AllocError::LockPoisoned)` if the guarding mutex was poisoned, or
+/// `Err(AllocError::OutOfCapacity)` if a new chunk could not be allocated.
+fn try_allocate_value(value: Value) -> Result<Handle, AllocError> {
+    VALUES.lock().map_err(|_| AllocError::LockPoisoned)?.try_allocate_value(value)
+}
+
+/// Tries to deallocate the value identified by `handle` from the global `VALUES` arena.
+///
+/// Returns `Err(AllocError::InvalidHandle)` if the chunk index is out of bounds or the
+/// handle's generation no longer matches the slot (i.e. the slot was freed since), or
+/// `Err(AllocError::LockPoisoned)` if the guarding mutex was poisoned.
+fn try_deallocate_value(handle: Handle) -> Result<(), AllocError> {
+    VALUES.lock().map_err(|_| AllocError::LockPoisoned)?.try_deallocate_value(handle)
+}
+
+/// Tries to read the value identified by `handle` from the global `VALUES` arena.
+///
+/// Returns `Err(AllocError::InvalidHandle)` if the handle is stale or its chunk index is
+/// out of bounds, or `Err(AllocError::LockPoisoned)` if the guarding mutex was poisoned.
+fn try_get_value(handle: Handle) -> Result<Value, AllocError> {
+    VALUES.lock().map_err(|_| AllocError::LockPoisoned)?.try_get_value(handle).copied()
+}
+
+fn main() -> PyResult<()> {
+    let handle = try_allocate_value(42)?;
+    println!("Value allocated in chunk {} at index {} (generation {})", handle.chunk_index, handle.value_index, handle.generation);
+    assert_eq!(try_get_value(handle), Ok(42));
+    try_deallocate_value(handle)?;
+    assert_eq!(try_get_value(handle), Err(AllocError::InvalidHandle));
+
+    // Demonstrate the inline/spill distinction: a single value stays inline in its slot,
+    // while a multi-value payload spills into a secondary chunk.
+    let mut values_arena: Arena<Value> = Arena::new();
+    let inline_handle = values_arena.try_allocate_value(7)?;
+    assert_eq!(*values_arena.try_get_value(inline_handle)?, 7);
+
+    let spilled_handle = values_arena.try_allocate_payload(vec![1, 2, 3])?;
+    assert_eq!(values_arena.try_get_payload(spilled_handle)?, vec![&1, &2, &3]);
+    values_arena.try_deallocate_value(spilled_handle)?;
+
+    // Round-trip an arbitrary Python object through the generic `Arena` to demonstrate
+    // that it now stores values directly instead of merely their length.
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let mut arena = PyArena::new();
+    let py_handle = arena.allocate(py.None())?;
+    assert_eq!(arena.__len__(), 1);
+    assert_eq!(arena.__getitem__(py, py_handle)?.as_ptr(), py.None().as_ptr());
+    arena.deallocate(py_handle)?;
+
+    // Demonstrate that a `MappedStore` survives being closed and reopened.
+    let mapped_path = std::env::temp_dir().join("chunky_queue_mapped_store_demo.bin");
+    let _ = std::fs::remove_file(&mapped_path);
+    let mut mapped = MappedStore::open(&mapped_path).expect("open mapped store");
+    let mapped_handle = mapped.try_allocate_value(99)?;
+    assert_eq!(mapped.try_get_value(mapped_handle), Ok(99));
+    mapped.close().expect("close mapped store");
+
+    let reopened = MappedStore::open(&mapped_path).expect("reopen mapped store");
+    assert_eq!(reopened.try_get_value(mapped_handle), Ok(99));
+    assert_eq!(reopened.len(), 1);
+    std::fs::remove_file(&mapped_path).expect("remove mapped store demo file");
+
+    Ok(())
+}
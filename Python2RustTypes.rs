@@ -1,76 +1,144 @@
-// Import necessary crates and modules
-#[macro_use]
-extern crate lazy_static;
-extern crate pyo3;
-
-use pyo3::prelude::*;
-use pyo3::types::{PyAny, PyString, PyInt};
-use std::sync::{Arc, Mutex};
-
-// Define a type alias for a tuple representing a value and its usage indicator
-type Value = i32;
-type UsageIndicator = bool;
-
-// Define a global, thread-safe container to hold the array
-lazy_static! {
-    static ref ARRAY: Arc<Mutex<Vec<(Value, UsageIndicator)>>> = Arc::new(Mutex::new(Vec::new()));
-}
-
-// Function to allocate a value in the array, reusing an unused slot if available
-fn allocate_value(value: Value) -> usize {
-    let mut array = ARRAY.lock().unwrap();
-    
-    // Look for an unused slot
-    for (index, &mut (ref val, ref mut used)) in array.iter_mut().enumerate() {
-        if !*used {
-            *val = value;  // Set the value
-            *used = true;  // Mark the slot as used
-            return index;  // Return the index of the slot
-        }
-    }
-
-    // No unused slot found, so append a new one
-    array.push((value, true));
-    array.len() - 1  // Return the index of the new slot
-}
-
-// Function to deallocate a value, marking its slot as unused
-fn deallocate_value(index: usize) {
-    let mut array = ARRAY.lock().unwrap();
-    if let Some(slot) = array.get_mut(index) {
-        slot.1 = false;  // Mark the slot as unused
-    }
-}
-
-// Function to handle PyObject and allocate a slot for its value in the array
-fn handle_pyobject(obj: &PyAny) -> PyResult<usize> {
-    if obj.is_instance::<PyString>()? {
-        let string_value: &str = obj.extract()?;
-        Ok(allocate_value(string_value.len() as i32))  // Example: use string length as value
-    } else if obj.is_instance::<PyInt>()? {
-        let int_value: i32 = obj.extract()?;
-        Ok(allocate_value(int_value))
-    } else {
-        Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>("Unsupported type"))
-    }
-}
-
-fn main() -> PyResult<()> {
-    let gil = Python::acquire_gil();
-    let py = gil.python();
-    let py_string = PyString::new(py, "Hello, world");
-    let py_int = PyInt::new(py, 42);
-
-    // Handle PyObjects and allocate slots for their values in the array
-    let string_index = handle_pyobject(py_string.as_ref(py))?;
-    let int_index = handle_pyobject(py_int.as_ref(py))?;
-    
-    println!("String value allocated at index {}", string_index);
-    println!("Integer value allocated at index {}", int_index);
-    
-    // Deallocate the values when done
-    deallocate_value(string_index);
-    deallocate_value(int_index);
-
-    Ok(())
-}
+// Import necessary crates and modules
+#[macro_use]
+extern crate lazy_static;
+extern crate pyo3;
+
+use pyo3::exceptions::{PyMemoryError, PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyAny, PyString, PyInt};
+use std::sync::{Arc, Mutex};
+
+// Define a type alias for a tuple representing a value and its usage indicator
+type Value = i32;
+type UsageIndicator = bool;
+
+/// A handle to a value stored in the array.
+///
+/// Carries the slot's generation alongside its index so that a handle
+/// obtained before the slot was deallocated and reused can be told apart
+/// from a handle to the new occupant, rather than silently aliasing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Handle {
+    index: usize,
+    generation: u32,
+}
+
+/// Errors that can occur while allocating, deallocating, or looking up a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AllocError {
+    /// The `ARRAY` mutex was poisoned by a panicking thread.
+    LockPoisoned,
+    /// The array could not grow to hold a new value.
+    OutOfCapacity,
+    /// The handle's generation no longer matches the slot's current generation, or its
+    /// index is out of bounds.
+    InvalidHandle,
+}
+
+impl std::fmt::Display for AllocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocError::LockPoisoned => write!(f, "allocator lock was poisoned"),
+            AllocError::OutOfCapacity => write!(f, "allocator is out of capacity"),
+            AllocError::InvalidHandle => write!(f, "handle is invalid or stale"),
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+impl From<AllocError> for PyErr {
+    fn from(err: AllocError) -> PyErr {
+        match err {
+            AllocError::LockPoisoned => PyRuntimeError::new_err(err.to_string()),
+            AllocError::OutOfCapacity => PyMemoryError::new_err(err.to_string()),
+            AllocError::InvalidHandle => PyRuntimeError::new_err(err.to_string()),
+        }
+    }
+}
+
+// Define a global, thread-safe container to hold the array.
+// Each slot is (value, used, generation); generation is bumped on every deallocate.
+lazy_static! {
+    static ref ARRAY: Arc<Mutex<Vec<(Value, UsageIndicator, u32)>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+// Function to try to allocate a value in the array, reusing an unused slot if available
+fn try_allocate_value(value: Value) -> Result<Handle, AllocError> {
+    let mut array = ARRAY.lock().map_err(|_| AllocError::LockPoisoned)?;
+
+    // Look for an unused slot
+    for (index, slot) in array.iter_mut().enumerate() {
+        if !slot.1 {
+            slot.0 = value;  // Set the value
+            slot.1 = true;  // Mark the slot as used
+            return Ok(Handle { index, generation: slot.2 });
+        }
+    }
+
+    // No unused slot found, so append a new one
+    array.try_reserve(1).map_err(|_| AllocError::OutOfCapacity)?;
+    array.push((value, true, 0));
+    Ok(Handle { index: array.len() - 1, generation: 0 })
+}
+
+// Function to try to deallocate a value, marking its slot as unused, provided the handle's
+// generation still matches the slot's current generation.
+//
+// Returns `Err(AllocError::InvalidHandle)` if the index is out of bounds, the slot was
+// already unused, or the handle was stale.
+fn try_deallocate_value(handle: Handle) -> Result<(), AllocError> {
+    let mut array = ARRAY.lock().map_err(|_| AllocError::LockPoisoned)?;
+    let slot = array.get_mut(handle.index).ok_or(AllocError::InvalidHandle)?;
+    if slot.1 && slot.2 == handle.generation {
+        slot.1 = false;  // Mark the slot as unused
+        slot.2 = slot.2.wrapping_add(1);  // Bump the generation
+        Ok(())
+    } else {
+        Err(AllocError::InvalidHandle)
+    }
+}
+
+// Function to try to read the value for a handle.
+//
+// Returns `Err(AllocError::InvalidHandle)` if the handle is stale or its index is out of bounds.
+fn try_get_value(handle: Handle) -> Result<Value, AllocError> {
+    let array = ARRAY.lock().map_err(|_| AllocError::LockPoisoned)?;
+    array.get(handle.index)
+        .filter(|slot| slot.1 && slot.2 == handle.generation)
+        .map(|slot| slot.0)
+        .ok_or(AllocError::InvalidHandle)
+}
+
+// Function to handle PyObject and allocate a slot for its value in the array
+fn handle_pyobject(obj: &PyAny) -> PyResult<Handle> {
+    if obj.is_instance::<PyString>()? {
+        let string_value: &str = obj.extract()?;
+        Ok(try_allocate_value(string_value.len() as i32)?)  // Example: use string length as value
+    } else if obj.is_instance::<PyInt>()? {
+        let int_value: i32 = obj.extract()?;
+        Ok(try_allocate_value(int_value)?)
+    } else {
+        Err(PyTypeError::new_err("Unsupported type"))
+    }
+}
+
+fn main() -> PyResult<()> {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let py_string = PyString::new(py, "Hello, world");
+    let py_int = PyInt::new(py, 42);
+
+    // Handle PyObjects and allocate slots for their values in the array
+    let string_handle = handle_pyobject(py_string.as_ref(py))?;
+    let int_handle = handle_pyobject(py_int.as_ref(py))?;
+
+    println!("String value allocated at index {} (generation {})", string_handle.index, string_handle.generation);
+    println!("Integer value allocated at index {} (generation {})", int_handle.index, int_handle.generation);
+
+    // Deallocate the values when done
+    try_deallocate_value(string_handle)?;
+    try_deallocate_value(int_handle)?;
+
+    Ok(())
+}